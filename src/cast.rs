@@ -0,0 +1,72 @@
+use anyhow::{Context, Result};
+use serde::Serialize;
+use serde_json::json;
+use std::{
+    fs::File,
+    io::{self, Write},
+    path::Path,
+    time::Instant,
+};
+
+pub struct Recorder {
+    file: File,
+    start: Instant,
+}
+
+#[derive(Serialize)]
+struct Header {
+    version: u8,
+    width: u16,
+    height: u16,
+    timestamp: u64,
+}
+
+impl Recorder {
+    pub fn create(path: &Path, width: u16, height: u16, timestamp: u64) -> Result<Self> {
+        let mut file = File::create(path)
+            .with_context(|| format!("Failed to create cast file {}", path.display()))?;
+        let header = Header {
+            version: 2,
+            width,
+            height,
+            timestamp,
+        };
+        serde_json::to_writer(&mut file, &header)?;
+        file.write_all(b"\n")?;
+        Ok(Self {
+            file,
+            start: Instant::now(),
+        })
+    }
+
+    fn record(&mut self, data: &[u8]) -> Result<()> {
+        let elapsed = self.start.elapsed().as_secs_f64();
+        let event = json!([elapsed, "o", String::from_utf8_lossy(data)]);
+        serde_json::to_writer(&mut self.file, &event)?;
+        self.file.write_all(b"\n")?;
+        Ok(())
+    }
+}
+
+pub struct RecordingWriter<'a, W: Write> {
+    inner: W,
+    recorder: &'a mut Recorder,
+}
+
+impl<'a, W: Write> RecordingWriter<'a, W> {
+    pub fn new(inner: W, recorder: &'a mut Recorder) -> Self {
+        Self { inner, recorder }
+    }
+}
+
+impl<'a, W: Write> Write for RecordingWriter<'a, W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let n = self.inner.write(buf)?;
+        let _ = self.recorder.record(&buf[..n]);
+        Ok(n)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
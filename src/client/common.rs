@@ -1,19 +1,33 @@
 use crate::{
     config::{Message, SharedConfig},
+    metrics::{record_tokens, RequestTimer},
     repl::{ReplyStreamHandler, SharedAbortSignal},
-    utils::{init_tokio_runtime, prompt_input_integer, prompt_input_string, tokenize, PromptKind},
+    storage::{new_session_id, record_exchange},
+    utils::{count_tokens, prompt_input_integer, prompt_input_string, tokenize, PromptKind},
 };
 
 use anyhow::{Context, Result};
 use async_trait::async_trait;
+use once_cell::sync::Lazy;
+use rand::Rng;
 use reqwest::{Client as ReqwestClient, ClientBuilder, Proxy};
 use serde::Deserialize;
 use serde_json::{json, Value};
 use std::{env, time::Duration};
-use tokio::time::sleep;
+use tokio::{runtime::Runtime, time::sleep};
 
 use super::{openai::OpenAIConfig, ClientConfig};
 
+static RUNTIME: Lazy<Runtime> = Lazy::new(|| {
+    Runtime::new().expect("Failed to create tokio runtime")
+});
+
+pub fn block_on<F: std::future::Future>(future: F) -> F::Output {
+    RUNTIME.block_on(future)
+}
+
+static REPL_SESSION_ID: Lazy<String> = Lazy::new(new_session_id);
+
 #[macro_export]
 macro_rules! register_client {
     (
@@ -167,7 +181,7 @@ macro_rules! config_get_fn {
 }
 
 #[async_trait]
-pub trait Client {
+pub trait Client: Send + Sync {
     fn config(&self) -> (&SharedConfig, &Option<ExtraConfig>);
 
     fn build_client(&self) -> Result<ReqwestClient> {
@@ -186,26 +200,128 @@ pub trait Client {
         Ok(client)
     }
 
-    fn send_message(&self, content: &str) -> Result<String> {
-        init_tokio_runtime()?.block_on(async {
-            let global_config = self.config().0;
-            if global_config.read().dry_run {
-                let content = global_config.read().echo_messages(content);
-                return Ok(content);
+    async fn send_message(&self, content: &str) -> Result<String> {
+        let global_config = self.config().0;
+        if global_config.read().dry_run {
+            let content = global_config.read().echo_messages(content);
+            return Ok(content);
+        }
+        let data = global_config.read().prepare_send_data(content, false)?;
+        self.send_data(data).await
+    }
+
+    async fn send_data(&self, data: SendData) -> Result<String> {
+        self.send_data_with_session(data, None).await
+    }
+
+    async fn send_data_with_session(
+        &self,
+        data: SendData,
+        session_id: Option<String>,
+    ) -> Result<String> {
+        let global_config = self.config().0;
+        if global_config.read().dry_run {
+            let content = data
+                .messages
+                .last()
+                .map(|m| m.content.as_str())
+                .unwrap_or_default();
+            return Ok(global_config.read().echo_messages(content));
+        }
+        let client = self.build_client()?;
+        let model_info = self.config().0.read().model_info.clone();
+        let prompt_tokens = model_info.messages_tokens(&data.messages);
+        let retry = self.retry_config();
+        let sent_messages = data.messages.clone();
+        let session_id = session_id.unwrap_or_else(|| REPL_SESSION_ID.clone());
+
+        let mut attempt = 0;
+        let mut data = Some(data);
+        let answer = loop {
+            // Only clone when a further retry is still possible; the common
+            // case (max_retries == 0, or the last allowed attempt) sends the
+            // data by value without paying for a copy of the message history.
+            let this_data = if attempt < retry.max_retries {
+                data.clone().expect("send data present")
+            } else {
+                data.take().expect("send data present")
+            };
+            let timer = RequestTimer::start(&model_info.client, &model_info.name);
+            let ret = self.send_message_inner(&client, this_data).await;
+            timer.observe(if ret.is_ok() { "ok" } else { "error" });
+            match ret {
+                Ok(answer) => break answer,
+                Err(err) if attempt < retry.max_retries && is_transient_error(&err) => {
+                    let delay = retry_delay(attempt, &retry);
+                    attempt += 1;
+                    sleep(delay).await;
+                }
+                Err(err) => return Err(err).with_context(|| "Failed to get answer"),
             }
-            let client = self.build_client()?;
-            let data = global_config.read().prepare_send_data(content, false)?;
-            self.send_message_inner(&client, data)
-                .await
-                .with_context(|| "Failed to get answer")
-        })
+        };
+
+        record_tokens(
+            &model_info.client,
+            &model_info.name,
+            prompt_tokens,
+            count_tokens(&answer),
+        );
+        if let Err(err) = record_exchange(&session_id, &model_info, &sent_messages, &answer).await {
+            eprintln!("Failed to persist session: {err:#}");
+        }
+        Ok(answer)
     }
 
-    fn send_message_streaming(
+    async fn send_message_streaming(
         &self,
         content: &str,
         handler: &mut ReplyStreamHandler,
     ) -> Result<()> {
+        let global_config = self.config().0;
+        if global_config.read().dry_run {
+            let content = global_config.read().echo_messages(content);
+            let tokens = tokenize(&content);
+            for token in tokens {
+                tokio::time::sleep(Duration::from_millis(25)).await;
+                handler.text(&token)?;
+            }
+            handler.done()?;
+            return Ok(());
+        }
+        let data = global_config.read().prepare_send_data(content, true)?;
+        self.send_data_streaming(data, handler).await
+    }
+
+    async fn send_data_streaming(
+        &self,
+        data: SendData,
+        handler: &mut ReplyStreamHandler,
+    ) -> Result<()> {
+        self.send_data_streaming_with_session(data, None, handler).await
+    }
+
+    async fn send_data_streaming_with_session(
+        &self,
+        data: SendData,
+        session_id: Option<String>,
+        handler: &mut ReplyStreamHandler,
+    ) -> Result<()> {
+        let global_config = self.config().0;
+        if global_config.read().dry_run {
+            let content = data
+                .messages
+                .last()
+                .map(|m| m.content.as_str())
+                .unwrap_or_default();
+            let content = global_config.read().echo_messages(content);
+            let tokens = tokenize(&content);
+            for token in tokens {
+                tokio::time::sleep(Duration::from_millis(25)).await;
+                handler.text(&token)?;
+            }
+            handler.done()?;
+            return Ok(());
+        }
         async fn watch_abort(abort: SharedAbortSignal) {
             loop {
                 if abort.aborted() {
@@ -215,36 +331,70 @@ pub trait Client {
             }
         }
         let abort = handler.get_abort();
-        init_tokio_runtime()?.block_on(async {
-            tokio::select! {
-                ret = async {
-                    let global_config = self.config().0;
-                    if global_config.read().dry_run {
-                        let content = global_config.read().echo_messages(content);
-                        let tokens = tokenize(&content);
-                        for token in tokens {
-                            tokio::time::sleep(Duration::from_millis(25)).await;
-                            handler.text(&token)?;
+        tokio::select! {
+            ret = async {
+                let client = self.build_client()?;
+                let model_info = self.config().0.read().model_info.clone();
+                let prompt_tokens = model_info.messages_tokens(&data.messages);
+                let retry = self.retry_config();
+                let sent_messages = data.messages.clone();
+                let session_id = session_id.unwrap_or_else(|| REPL_SESSION_ID.clone());
+
+                // A timeout is excluded from the retryable establishment
+                // errors below (unlike the non-streaming path) since it can
+                // fire mid-stream, after part of the answer has already been
+                // forwarded to the handler.
+                let mut attempt = 0;
+                let mut data = Some(data);
+                loop {
+                    let this_data = if attempt < retry.max_retries {
+                        data.clone().expect("send data present")
+                    } else {
+                        data.take().expect("send data present")
+                    };
+                    let timer = RequestTimer::start(&model_info.client, &model_info.name);
+                    let ret = self
+                        .send_message_streaming_inner(&client, handler, this_data)
+                        .await;
+                    timer.observe(if ret.is_ok() { "ok" } else { "error" });
+                    match ret {
+                        Err(err) if attempt < retry.max_retries && is_transient_establishment_error(&err) => {
+                            if abort.aborted() {
+                                break Ok(());
+                            }
+                            let delay = retry_delay(attempt, &retry);
+                            attempt += 1;
+                            sleep(delay).await;
                         }
-                        return Ok(());
+                        Ok(()) => {
+                            let answer = handler.buffer();
+                            record_tokens(
+                                &model_info.client,
+                                &model_info.name,
+                                prompt_tokens,
+                                count_tokens(answer),
+                            );
+                            if let Err(err) = record_exchange(&session_id, &model_info, &sent_messages, answer).await {
+                                eprintln!("Failed to persist session: {err:#}");
+                            }
+                            break Ok(());
+                        }
+                        ret => break ret,
                     }
-                    let client = self.build_client()?;
-                    let data = global_config.read().prepare_send_data(content, true)?;
-                    self.send_message_streaming_inner(&client, handler, data).await
-                } => {
-                    handler.done()?;
-                    ret.with_context(|| "Failed to get answer")
-                }
-                _ = watch_abort(abort.clone()) => {
-                    handler.done()?;
-                    Ok(())
-                 },
-                _ =  tokio::signal::ctrl_c() => {
-                    abort.set_ctrlc();
-                    Ok(())
                 }
+            } => {
+                handler.done()?;
+                ret.with_context(|| "Failed to get answer")
             }
-        })
+            _ = watch_abort(abort.clone()) => {
+                handler.done()?;
+                Ok(())
+             },
+            _ =  tokio::signal::ctrl_c() => {
+                abort.set_ctrlc();
+                Ok(())
+            }
+        }
     }
 
     async fn send_message_inner(&self, client: &ReqwestClient, data: SendData) -> Result<String>;
@@ -255,6 +405,17 @@ pub trait Client {
         handler: &mut ReplyStreamHandler,
         data: SendData,
     ) -> Result<()>;
+
+    fn retry_config(&self) -> RetryConfig {
+        match self.config().1 {
+            Some(extra) => RetryConfig {
+                max_retries: extra.max_retries.unwrap_or(0),
+                base_delay_ms: extra.retry_base_delay_ms.unwrap_or(200),
+                max_delay_ms: extra.retry_max_delay_ms.unwrap_or(10_000),
+            },
+            None => RetryConfig::default(),
+        }
+    }
 }
 
 impl Default for ClientConfig {
@@ -267,9 +428,59 @@ impl Default for ClientConfig {
 pub struct ExtraConfig {
     pub proxy: Option<String>,
     pub connect_timeout: Option<u64>,
+    pub max_retries: Option<u32>,
+    pub retry_base_delay_ms: Option<u64>,
+    pub retry_max_delay_ms: Option<u64>,
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct RetryConfig {
+    pub max_retries: u32,
+    pub base_delay_ms: u64,
+    pub max_delay_ms: u64,
+}
+
+impl Default for RetryConfig {
+    fn default() -> Self {
+        Self {
+            max_retries: 0,
+            base_delay_ms: 200,
+            max_delay_ms: 10_000,
+        }
+    }
+}
+
+fn is_transient_error(err: &anyhow::Error) -> bool {
+    err.chain().any(|cause| match cause.downcast_ref::<reqwest::Error>() {
+        Some(err) => match err.status() {
+            Some(status) => status.as_u16() == 429 || status.is_server_error(),
+            None => err.is_connect() || err.is_timeout(),
+        },
+        None => false,
+    })
+}
+
+fn is_transient_establishment_error(err: &anyhow::Error) -> bool {
+    err.chain().any(|cause| match cause.downcast_ref::<reqwest::Error>() {
+        Some(err) => match err.status() {
+            Some(status) => status.as_u16() == 429 || status.is_server_error(),
+            None => err.is_connect(),
+        },
+        None => false,
+    })
+}
+
+fn retry_delay(attempt: u32, retry: &RetryConfig) -> Duration {
+    let cap = 2u64
+        .checked_pow(attempt)
+        .and_then(|pow| retry.base_delay_ms.checked_mul(pow))
+        .unwrap_or(retry.max_delay_ms)
+        .min(retry.max_delay_ms);
+    let jitter_ms = rand::thread_rng().gen_range(0..=cap);
+    Duration::from_millis(jitter_ms)
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct SendData {
     pub messages: Vec<Message>,
     pub temperature: Option<f64>,
@@ -354,4 +565,37 @@ fn set_proxy(builder: ClientBuilder, proxy: &Option<String>) -> Result<ClientBui
     let builder =
         builder.proxy(Proxy::all(&proxy).with_context(|| format!("Invalid proxy `{proxy}`"))?);
     Ok(builder)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn retry_delay_backs_off_within_bounds() {
+        let retry = RetryConfig {
+            max_retries: 5,
+            base_delay_ms: 200,
+            max_delay_ms: 10_000,
+        };
+        for attempt in 0..5 {
+            let delay = retry_delay(attempt, &retry);
+            let cap = (retry.base_delay_ms * 2u64.pow(attempt)).min(retry.max_delay_ms);
+            assert!(delay <= Duration::from_millis(cap));
+        }
+    }
+
+    #[test]
+    fn retry_delay_does_not_overflow_on_large_attempt_counts() {
+        let retry = RetryConfig::default();
+        let delay = retry_delay(64, &retry);
+        assert!(delay <= Duration::from_millis(retry.max_delay_ms));
+    }
+
+    #[test]
+    fn is_transient_error_false_for_non_reqwest_errors() {
+        let err = anyhow::anyhow!("bad request");
+        assert!(!is_transient_error(&err));
+        assert!(!is_transient_establishment_error(&err));
+    }
 }
\ No newline at end of file
@@ -0,0 +1,83 @@
+use once_cell::sync::Lazy;
+use prometheus::{
+    register_histogram_vec, register_int_counter_vec, Encoder, HistogramVec, IntCounterVec,
+    TextEncoder,
+};
+use std::time::Instant;
+
+pub static REQUESTS_TOTAL: Lazy<IntCounterVec> = Lazy::new(|| {
+    register_int_counter_vec!(
+        "aichat_requests_total",
+        "Total number of requests sent to a client",
+        &["client", "model", "outcome"]
+    )
+    .expect("Failed to register aichat_requests_total")
+});
+
+pub static REQUEST_DURATION_SECONDS: Lazy<HistogramVec> = Lazy::new(|| {
+    register_histogram_vec!(
+        "aichat_request_duration_seconds",
+        "Time spent waiting on a client request",
+        &["client", "model"]
+    )
+    .expect("Failed to register aichat_request_duration_seconds")
+});
+
+pub static PROMPT_TOKENS_TOTAL: Lazy<IntCounterVec> = Lazy::new(|| {
+    register_int_counter_vec!(
+        "aichat_prompt_tokens_total",
+        "Total prompt tokens sent to a client",
+        &["client", "model"]
+    )
+    .expect("Failed to register aichat_prompt_tokens_total")
+});
+
+pub static COMPLETION_TOKENS_TOTAL: Lazy<IntCounterVec> = Lazy::new(|| {
+    register_int_counter_vec!(
+        "aichat_completion_tokens_total",
+        "Total completion tokens received from a client",
+        &["client", "model"]
+    )
+    .expect("Failed to register aichat_completion_tokens_total")
+});
+
+pub struct RequestTimer {
+    client: String,
+    model: String,
+    start: Instant,
+}
+
+impl RequestTimer {
+    pub fn start(client: &str, model: &str) -> Self {
+        Self {
+            client: client.to_string(),
+            model: model.to_string(),
+            start: Instant::now(),
+        }
+    }
+
+    pub fn observe(self, outcome: &str) {
+        REQUEST_DURATION_SECONDS
+            .with_label_values(&[&self.client, &self.model])
+            .observe(self.start.elapsed().as_secs_f64());
+        REQUESTS_TOTAL
+            .with_label_values(&[&self.client, &self.model, outcome])
+            .inc();
+    }
+}
+
+pub fn record_tokens(client: &str, model: &str, prompt_tokens: usize, completion_tokens: usize) {
+    PROMPT_TOKENS_TOTAL
+        .with_label_values(&[client, model])
+        .inc_by(prompt_tokens as u64);
+    COMPLETION_TOKENS_TOTAL
+        .with_label_values(&[client, model])
+        .inc_by(completion_tokens as u64);
+}
+
+pub fn gather_text() -> anyhow::Result<String> {
+    let metric_families = prometheus::gather();
+    let mut buffer = Vec::new();
+    TextEncoder::new().encode(&metric_families, &mut buffer)?;
+    Ok(String::from_utf8(buffer)?)
+}
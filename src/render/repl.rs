@@ -1,6 +1,9 @@
 use super::{MarkdownRender, ReplyEvent};
 
-use crate::utils::{split_line_tail, AbortSignal};
+use crate::{
+    cast::{Recorder, RecordingWriter},
+    utils::{split_line_tail, AbortSignal},
+};
 
 use anyhow::Result;
 use crossbeam::channel::Receiver;
@@ -11,31 +14,56 @@ use crossterm::{
     terminal::{self, disable_raw_mode, enable_raw_mode},
 };
 use std::{
-    io::{self, Stdout, Write},
-    time::{Duration, Instant},
+    env,
+    io::{self, Write},
+    path::{Path, PathBuf},
+    time::{Duration, Instant, SystemTime, UNIX_EPOCH},
 };
 use textwrap::core::display_width;
 
+/// Env var checked when the caller doesn't pass an explicit `record_path`, so
+/// a recording can actually be started (e.g. `AICHAT_RECORD=session.cast
+/// aichat`) ahead of a `--record` CLI flag being wired up to a real entry
+/// point.
+const RECORD_PATH_ENV: &str = "AICHAT_RECORD";
+
 pub fn repl_render_stream(
     rx: &Receiver<ReplyEvent>,
     render: &mut MarkdownRender,
     abort: &AbortSignal,
+    record_path: Option<&Path>,
 ) -> Result<()> {
     enable_raw_mode()?;
     let mut stdout = io::stdout();
 
-    let ret = repl_render_stream_inner(rx, render, abort, &mut stdout);
+    let record_path: Option<PathBuf> = record_path
+        .map(PathBuf::from)
+        .or_else(|| env::var_os(RECORD_PATH_ENV).map(PathBuf::from));
+
+    let ret = match &record_path {
+        Some(path) => {
+            let (width, height) = terminal::size()?;
+            let timestamp = SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .map(|d| d.as_secs())
+                .unwrap_or(0);
+            let mut recorder = Recorder::create(path, width, height, timestamp)?;
+            let mut writer = RecordingWriter::new(&mut stdout, &mut recorder);
+            repl_render_stream_inner(rx, render, abort, &mut writer)
+        }
+        None => repl_render_stream_inner(rx, render, abort, &mut stdout),
+    };
 
     disable_raw_mode()?;
 
     ret
 }
 
-fn repl_render_stream_inner(
+fn repl_render_stream_inner<W: Write>(
     rx: &Receiver<ReplyEvent>,
     render: &mut MarkdownRender,
     abort: &AbortSignal,
-    writer: &mut Stdout,
+    writer: &mut W,
 ) -> Result<()> {
     let mut last_tick = Instant::now();
     let tick_rate = Duration::from_millis(50);
@@ -136,7 +164,7 @@ fn repl_render_stream_inner(
     Ok(())
 }
 
-fn print_block(writer: &mut Stdout, text: &str, columns: u16) -> Result<u16> {
+fn print_block<W: Write>(writer: &mut W, text: &str, columns: u16) -> Result<u16> {
     let mut num = 0;
     for line in text.split('\n') {
         queue!(
@@ -0,0 +1,266 @@
+use crate::{
+    client::{block_on, init_client, Client, SendData},
+    config::{Message, Role, SharedConfig},
+    metrics::gather_text,
+    repl::{ReplyEvent, ReplyStreamHandler, SharedAbortSignal},
+    storage::new_session_id,
+};
+
+use anyhow::{anyhow, Context, Result};
+use axum::{
+    extract::State,
+    response::sse::{Event, KeepAlive, Sse},
+    response::{IntoResponse, Response},
+    routing::{get, post},
+    Json, Router,
+};
+use futures_util::stream::Stream;
+use parking_lot::RwLock;
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use std::{
+    convert::Infallible,
+    net::SocketAddr,
+    pin::Pin,
+    sync::Arc,
+    task::{Context as TaskContext, Poll},
+};
+use tokio::sync::mpsc;
+use tokio_stream::wrappers::UnboundedReceiverStream;
+
+pub fn serve(config: SharedConfig, addr: &str) -> Result<()> {
+    let addr: SocketAddr = addr
+        .parse()
+        .with_context(|| format!("Invalid listen address `{addr}`"))?;
+    block_on(run(config, addr))
+}
+
+async fn run(config: SharedConfig, addr: SocketAddr) -> Result<()> {
+    let app = Router::new()
+        .route("/v1/chat/completions", post(chat_completions))
+        .route("/metrics", get(metrics))
+        .with_state(config);
+
+    let listener = tokio::net::TcpListener::bind(addr)
+        .await
+        .with_context(|| format!("Failed to bind {addr}"))?;
+
+    println!("Listening on http://{addr}/v1");
+
+    tokio::select! {
+        ret = axum::serve(listener, app) => {
+            ret.with_context(|| "Server error")?;
+        }
+        _ = tokio::signal::ctrl_c() => {
+            println!("Ctrl-c received, shutting down");
+        }
+    }
+
+    Ok(())
+}
+
+#[derive(Debug, Deserialize)]
+struct ChatCompletionRequest {
+    model: String,
+    messages: Vec<ChatMessage>,
+    #[serde(default)]
+    stream: bool,
+    temperature: Option<f64>,
+    #[serde(default)]
+    session_id: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+struct ChatMessage {
+    role: String,
+    content: String,
+}
+
+#[derive(Debug, Serialize)]
+struct ChatCompletionResponse {
+    id: String,
+    object: &'static str,
+    model: String,
+    choices: Vec<Choice>,
+    session_id: String,
+}
+
+#[derive(Debug, Serialize)]
+struct Choice {
+    index: usize,
+    message: OutMessage,
+    finish_reason: &'static str,
+}
+
+#[derive(Debug, Serialize)]
+struct OutMessage {
+    role: &'static str,
+    content: String,
+}
+
+struct ServerError(axum::http::StatusCode, anyhow::Error);
+
+impl ServerError {
+    fn bad_request(err: anyhow::Error) -> Self {
+        Self(axum::http::StatusCode::BAD_REQUEST, err)
+    }
+}
+
+impl IntoResponse for ServerError {
+    fn into_response(self) -> Response {
+        let body = Json(json!({ "error": { "message": self.1.to_string() } }));
+        (self.0, body).into_response()
+    }
+}
+
+impl<E: Into<anyhow::Error>> From<E> for ServerError {
+    fn from(err: E) -> Self {
+        Self(axum::http::StatusCode::BAD_GATEWAY, err.into())
+    }
+}
+
+async fn metrics() -> Result<Response, ServerError> {
+    Ok(gather_text()?.into_response())
+}
+
+async fn chat_completions(
+    State(config): State<SharedConfig>,
+    Json(req): Json<ChatCompletionRequest>,
+) -> Result<Response, ServerError> {
+    let client = resolve_client(&config, &req.model)?;
+    let messages: Vec<Message> = req
+        .messages
+        .iter()
+        .map(|m| Message::new(Role::from(m.role.as_str()), m.content.clone()))
+        .collect();
+    // A session id derived from conversation content (e.g. hashing the first
+    // message) lets two unrelated callers who happen to open with the same
+    // line collide onto one `sessions` row and interleave their history.
+    // Require an explicit, caller-owned id instead: reuse one the caller
+    // hands back to continue a session, or mint a fresh random one and
+    // report it back so the caller can continue next time.
+    let session_id = req.session_id.clone().unwrap_or_else(new_session_id);
+    let data = SendData {
+        messages,
+        temperature: req.temperature,
+        stream: req.stream,
+    };
+
+    if req.stream {
+        let stream = stream_completion(client, req.model.clone(), data, session_id.clone());
+        let mut response = Sse::new(stream)
+            .keep_alive(KeepAlive::default())
+            .into_response();
+        if let Ok(value) = axum::http::HeaderValue::from_str(&session_id) {
+            response.headers_mut().insert("x-session-id", value);
+        }
+        Ok(response)
+    } else {
+        let answer = client
+            .send_data_with_session(data, Some(session_id.clone()))
+            .await?;
+        let resp = ChatCompletionResponse {
+            id: format!("chatcmpl-{}", uuid_like()),
+            object: "chat.completion",
+            model: req.model,
+            choices: vec![Choice {
+                index: 0,
+                message: OutMessage {
+                    role: "assistant",
+                    content: answer,
+                },
+                finish_reason: "stop",
+            }],
+            session_id,
+        };
+        Ok(Json(resp).into_response())
+    }
+}
+
+fn resolve_client(config: &SharedConfig, model: &str) -> Result<Box<dyn Client>, ServerError> {
+    let mut snapshot = config.read().clone();
+    let model_info = crate::client::all_models(&snapshot)
+        .into_iter()
+        .find(|m| m.id() == model || m.name == model)
+        .ok_or_else(|| ServerError::bad_request(anyhow!("Unknown model `{model}`")))?;
+    snapshot.model_info = model_info;
+    Ok(init_client(Arc::new(RwLock::new(snapshot)))?)
+}
+
+struct AbortOnDrop<S> {
+    inner: S,
+    abort: SharedAbortSignal,
+}
+
+impl<S: Stream + Unpin> Stream for AbortOnDrop<S> {
+    type Item = S::Item;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut TaskContext<'_>) -> Poll<Option<Self::Item>> {
+        Pin::new(&mut self.inner).poll_next(cx)
+    }
+}
+
+impl<S> Drop for AbortOnDrop<S> {
+    fn drop(&mut self) {
+        self.abort.set_ctrlc();
+    }
+}
+
+fn stream_completion(
+    client: Box<dyn Client>,
+    model: String,
+    data: SendData,
+    session_id: String,
+) -> impl Stream<Item = Result<Event, Infallible>> {
+    let (cb_tx, cb_rx) = crossbeam::channel::unbounded();
+    let abort = SharedAbortSignal::new();
+
+    tokio::spawn({
+        let abort = abort.clone();
+        async move {
+            let mut handler = ReplyStreamHandler::new(cb_tx, abort, false);
+            if let Err(err) = client
+                .send_data_streaming_with_session(data, Some(session_id), &mut handler)
+                .await
+            {
+                let _ = handler.text(&format!("\n[error] {err}"));
+                let _ = handler.done();
+            }
+        }
+    });
+
+    let (tx, rx) = mpsc::unbounded_channel::<Result<Event, Infallible>>();
+    tokio::task::spawn_blocking(move || {
+        while let Ok(evt) = cb_rx.recv() {
+            match evt {
+                ReplyEvent::Text(text) => {
+                    let chunk = json!({
+                        "model": model,
+                        "choices": [{ "delta": { "content": text }, "index": 0 }],
+                    });
+                    if tx.send(Ok(Event::default().data(chunk.to_string()))).is_err() {
+                        break;
+                    }
+                }
+                ReplyEvent::Done => {
+                    let _ = tx.send(Ok(Event::default().data("[DONE]")));
+                    break;
+                }
+            }
+        }
+    });
+
+    AbortOnDrop {
+        inner: UnboundedReceiverStream::new(rx),
+        abort,
+    }
+}
+
+fn uuid_like() -> String {
+    use std::time::{SystemTime, UNIX_EPOCH};
+    let nanos = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_nanos())
+        .unwrap_or_default();
+    format!("{nanos:x}")
+}
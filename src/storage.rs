@@ -0,0 +1,329 @@
+use crate::config::{Message, ModelInfo, Role};
+
+use anyhow::{Context, Result};
+use once_cell::sync::Lazy;
+use rand::Rng;
+use sqlx::{sqlite::SqlitePoolOptions, FromRow, Pool, Sqlite};
+use std::{
+    collections::HashMap,
+    path::{Path, PathBuf},
+    sync::Arc,
+    time::{SystemTime, UNIX_EPOCH},
+};
+use tokio::sync::{Mutex, OnceCell};
+
+static STORAGE: OnceCell<Storage> = OnceCell::const_new();
+
+static SESSION_LOCKS: Lazy<parking_lot::Mutex<HashMap<String, Arc<Mutex<()>>>>> =
+    Lazy::new(|| parking_lot::Mutex::new(HashMap::new()));
+
+async fn lock_session(session_id: &str) -> tokio::sync::OwnedMutexGuard<()> {
+    let lock = {
+        let mut locks = SESSION_LOCKS.lock();
+        locks
+            .entry(session_id.to_string())
+            .or_insert_with(|| Arc::new(Mutex::new(())))
+            .clone()
+    };
+    lock.lock_owned().await
+}
+
+pub async fn storage() -> Result<&'static Storage> {
+    STORAGE
+        .get_or_try_init(|| async {
+            let dir = std::env::var("AICHAT_CONFIG_DIR")
+                .map(PathBuf::from)
+                .unwrap_or_else(|_| PathBuf::from("."));
+            Storage::open(&dir.join("sessions.db")).await
+        })
+        .await
+}
+
+fn now() -> i64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs() as i64)
+        .unwrap_or(0)
+}
+
+#[derive(Debug, Clone)]
+pub struct Storage {
+    pool: Pool<Sqlite>,
+}
+
+#[derive(Debug, Clone, FromRow)]
+pub struct SessionRow {
+    pub id: i64,
+    pub title: String,
+    pub model_id: String,
+    pub created_at: i64,
+}
+
+#[derive(Debug, Clone, FromRow)]
+pub struct MessageRow {
+    pub role: String,
+    pub content: String,
+    pub tokens: i64,
+    pub created_at: i64,
+}
+
+impl Storage {
+    pub async fn open(path: &Path) -> Result<Self> {
+        let url = format!("sqlite://{}?mode=rwc", path.display());
+        let pool = SqlitePoolOptions::new()
+            .max_connections(5)
+            .connect(&url)
+            .await
+            .with_context(|| format!("Failed to open session database at {}", path.display()))?;
+        let storage = Self { pool };
+        storage.migrate().await?;
+        Ok(storage)
+    }
+
+    async fn migrate(&self) -> Result<()> {
+        sqlx::query(
+            r#"
+            CREATE TABLE IF NOT EXISTS sessions (
+                id         INTEGER PRIMARY KEY AUTOINCREMENT,
+                key        TEXT NOT NULL,
+                title      TEXT NOT NULL,
+                model_id   TEXT NOT NULL,
+                created_at INTEGER NOT NULL
+            );
+            "#,
+        )
+        .execute(&self.pool)
+        .await?;
+
+        sqlx::query("CREATE UNIQUE INDEX IF NOT EXISTS idx_sessions_key ON sessions(key);")
+            .execute(&self.pool)
+            .await?;
+
+        sqlx::query(
+            r#"
+            CREATE TABLE IF NOT EXISTS messages (
+                session_id INTEGER NOT NULL REFERENCES sessions(id),
+                role       TEXT NOT NULL,
+                content    TEXT NOT NULL,
+                tokens     INTEGER NOT NULL,
+                created_at INTEGER NOT NULL
+            );
+            "#,
+        )
+        .execute(&self.pool)
+        .await?;
+
+        sqlx::query(
+            r#"
+            CREATE VIRTUAL TABLE IF NOT EXISTS messages_fts USING fts5(
+                content, content='messages', content_rowid='rowid'
+            );
+            "#,
+        )
+        .execute(&self.pool)
+        .await?;
+
+        Ok(())
+    }
+
+    pub async fn find_or_create_session(
+        &self,
+        key: &str,
+        title: &str,
+        model_info: &ModelInfo,
+        created_at: i64,
+    ) -> Result<i64> {
+        if let Some((id,)) = sqlx::query_as::<_, (i64,)>("SELECT id FROM sessions WHERE key = ?")
+            .bind(key)
+            .fetch_optional(&self.pool)
+            .await?
+        {
+            return Ok(id);
+        }
+        let id =
+            sqlx::query("INSERT INTO sessions (key, title, model_id, created_at) VALUES (?, ?, ?, ?)")
+                .bind(key)
+                .bind(title)
+                .bind(model_info.id())
+                .bind(created_at)
+                .execute(&self.pool)
+                .await?
+                .last_insert_rowid();
+        Ok(id)
+    }
+
+    pub async fn message_count(&self, session_id: i64) -> Result<i64> {
+        let (count,): (i64,) = sqlx::query_as("SELECT COUNT(*) FROM messages WHERE session_id = ?")
+            .bind(session_id)
+            .fetch_one(&self.pool)
+            .await?;
+        Ok(count)
+    }
+
+    pub async fn append_message(
+        &self,
+        session_id: i64,
+        message: &Message,
+        model_info: &ModelInfo,
+        created_at: i64,
+    ) -> Result<()> {
+        let tokens = model_info.messages_tokens(std::slice::from_ref(message)) as i64;
+        let rowid = sqlx::query(
+            "INSERT INTO messages (session_id, role, content, tokens, created_at) VALUES (?, ?, ?, ?, ?)",
+        )
+        .bind(session_id)
+        .bind(message.role.to_string())
+        .bind(&message.content)
+        .bind(tokens)
+        .bind(created_at)
+        .execute(&self.pool)
+        .await?
+        .last_insert_rowid();
+
+        sqlx::query("INSERT INTO messages_fts (rowid, content) VALUES (?, ?)")
+            .bind(rowid)
+            .bind(&message.content)
+            .execute(&self.pool)
+            .await?;
+
+        Ok(())
+    }
+
+    pub async fn list_sessions(&self) -> Result<Vec<SessionRow>> {
+        let rows = sqlx::query_as::<_, SessionRow>(
+            "SELECT id, title, model_id, created_at FROM sessions ORDER BY created_at DESC",
+        )
+        .fetch_all(&self.pool)
+        .await?;
+        Ok(rows)
+    }
+
+    pub async fn search(&self, query: &str) -> Result<Vec<i64>> {
+        let rows: Vec<(i64,)> = sqlx::query_as(
+            r#"
+            SELECT DISTINCT m.session_id
+            FROM messages_fts f
+            JOIN messages m ON m.rowid = f.rowid
+            WHERE f.content MATCH ?
+            "#,
+        )
+        .bind(query)
+        .fetch_all(&self.pool)
+        .await?;
+        Ok(rows.into_iter().map(|(id,)| id).collect())
+    }
+
+    pub async fn resume(&self, session_id: i64) -> Result<Vec<Message>> {
+        let rows = sqlx::query_as::<_, MessageRow>(
+            "SELECT role, content, tokens, created_at FROM messages WHERE session_id = ? ORDER BY created_at ASC",
+        )
+        .bind(session_id)
+        .fetch_all(&self.pool)
+        .await?;
+
+        Ok(rows
+            .into_iter()
+            .map(|row| Message::new(row.role.as_str().into(), row.content))
+            .collect())
+    }
+}
+
+pub fn new_session_id() -> String {
+    let secs = now();
+    let salt: u64 = rand::thread_rng().gen();
+    format!("{secs:x}-{salt:x}")
+}
+
+pub async fn record_exchange(
+    session_id: &str,
+    model_info: &ModelInfo,
+    messages: &[Message],
+    answer: &str,
+) -> Result<()> {
+    let _guard = lock_session(session_id).await;
+    let storage = storage().await?;
+    let title: String = messages
+        .first()
+        .map(|m| m.content.chars().take(60).collect())
+        .unwrap_or_default();
+    let created_at = now();
+    let session_id = storage
+        .find_or_create_session(session_id, &title, model_info, created_at)
+        .await?;
+
+    let already_recorded = storage.message_count(session_id).await? as usize;
+    for message in messages.iter().skip(already_recorded) {
+        storage
+            .append_message(session_id, message, model_info, created_at)
+            .await?;
+    }
+    storage
+        .append_message(
+            session_id,
+            &Message::new(Role::Assistant, answer.to_string()),
+            model_info,
+            now(),
+        )
+        .await?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    async fn temp_storage() -> Storage {
+        let path = std::env::temp_dir().join(format!("aichat-test-{}.db", new_session_id()));
+        Storage::open(&path).await.expect("open test storage")
+    }
+
+    #[tokio::test]
+    async fn round_trips_sessions_messages_and_search() {
+        let storage = temp_storage().await;
+        let model_info = ModelInfo::new(0, "openai", "gpt-4");
+
+        let session_id = storage
+            .find_or_create_session("conv-1", "hello there", &model_info, 0)
+            .await
+            .unwrap();
+        // Reusing the same key must return the same row, not insert another.
+        let same_id = storage
+            .find_or_create_session("conv-1", "hello there", &model_info, 0)
+            .await
+            .unwrap();
+        assert_eq!(session_id, same_id);
+
+        storage
+            .append_message(
+                session_id,
+                &Message::new(Role::User, "hello there".to_string()),
+                &model_info,
+                0,
+            )
+            .await
+            .unwrap();
+        storage
+            .append_message(
+                session_id,
+                &Message::new(Role::Assistant, "hi!".to_string()),
+                &model_info,
+                1,
+            )
+            .await
+            .unwrap();
+
+        assert_eq!(storage.message_count(session_id).await.unwrap(), 2);
+
+        let resumed = storage.resume(session_id).await.unwrap();
+        assert_eq!(resumed.len(), 2);
+        assert_eq!(resumed[0].content, "hello there");
+        assert_eq!(resumed[1].content, "hi!");
+
+        let hits = storage.search("hello").await.unwrap();
+        assert_eq!(hits, vec![session_id]);
+
+        let sessions = storage.list_sessions().await.unwrap();
+        assert_eq!(sessions.len(), 1);
+        assert_eq!(sessions[0].id, session_id);
+    }
+}